@@ -1,9 +1,26 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    self, AnalyserNode, AudioContext, HtmlCanvasElement, MediaStream, MediaStreamConstraints,
+    self, AnalyserNode, AudioContext, AudioWorkletNode, GainNode, HtmlCanvasElement, MediaStream,
+    MediaStreamAudioSourceNode, MediaStreamConstraints, MessageEvent, MouseEvent,
 };
 
+// Note playback envelope, in seconds.
+const NOTE_ATTACK_SECONDS: f64 = 0.02;
+const NOTE_RELEASE_SECONDS: f64 = 0.3;
+const NOTE_PEAK_GAIN: f32 = 0.8;
+
+// Name registered by the AudioWorklet processor module (see
+// capture-processor.js), and the module URL it's loaded from.
+const CAPTURE_PROCESSOR_NAME: &str = "capture-processor";
+const CAPTURE_PROCESSOR_MODULE_URL: &str = "capture-processor.js";
+
 // Piano roll constants
 const A4_FREQUENCY: f64 = 440.0;
 const MIDI_A4: u8 = 69;
@@ -13,6 +30,237 @@ const WHITE_KEYS: [bool; 12] = [
     true, false, true, false, true, true, false, true, false, true, false, true,
 ];
 
+// Frequency axis scale used for the piano roll and waterfall, mirroring
+// the scale options found in Audacity's spectrogram settings.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScaleType {
+    Linear,
+    Log,
+    Mel,
+}
+
+impl ScaleType {
+    // Converts a frequency to the axis's internal scale space.
+    fn to_scaled(self, freq: f64) -> f64 {
+        match self {
+            ScaleType::Linear => freq,
+            ScaleType::Log => freq.ln(),
+            ScaleType::Mel => Self::freq_to_mel(freq),
+        }
+    }
+
+    // Inverts `to_scaled`, recovering a frequency from scale space.
+    fn from_scaled(self, scaled: f64) -> f64 {
+        match self {
+            ScaleType::Linear => scaled,
+            ScaleType::Log => scaled.exp(),
+            ScaleType::Mel => Self::mel_to_freq(scaled),
+        }
+    }
+
+    fn freq_to_mel(freq: f64) -> f64 {
+        2595.0 * (1.0 + freq / 700.0).log10()
+    }
+
+    fn mel_to_freq(mel: f64) -> f64 {
+        700.0 * (10f64.powf(mel / 2595.0) - 1.0)
+    }
+}
+
+// Converts a frequency to a (fractional) MIDI note number.
+fn frequency_to_midi_note(freq: f64) -> f64 {
+    12.0 * (freq / A4_FREQUENCY).log2() + MIDI_A4 as f64
+}
+
+// Converts a MIDI note number to its frequency in Hz.
+fn midi_note_to_frequency(note: f64) -> f64 {
+    A4_FREQUENCY * 2.0_f64.powf((note - MIDI_A4 as f64) / 12.0)
+}
+
+// Maps a bin's raw dB value (plus optional per-octave frequency
+// compensation) to a normalized 0..1 display value, Audacity-style.
+fn normalize_magnitude(
+    db: f64,
+    freq: f64,
+    gain_db: f64,
+    range_db: f64,
+    freq_gain_db_per_octave: f64,
+    min_frequency: f64,
+) -> f64 {
+    // The DC bin (freq == 0) has no defined octave distance from
+    // min_frequency, so skip the per-octave term rather than feeding
+    // log2(0) = -inf into the compensation.
+    let freq_compensation_db = if freq > 0.0 {
+        freq_gain_db_per_octave * (freq / min_frequency).log2()
+    } else {
+        0.0
+    };
+    let compensated_db = db + freq_compensation_db;
+    ((compensated_db + gain_db) / range_db + 1.0).clamp(0.0, 1.0)
+}
+
+// Refines a discrete FFT bin index to a fractional sub-bin offset by fitting
+// a parabola through the peak bin (`b`) and its two neighbours (`a`, `c`),
+// all in dB. Returns 0.0 if the three points are collinear (no curvature).
+fn parabolic_peak_delta(a: f64, b: f64, c: f64) -> f64 {
+    let denominator = a - 2.0 * b + c;
+    if denominator.abs() > f64::EPSILON {
+        0.5 * (a - c) / denominator
+    } else {
+        0.0
+    }
+}
+
+// Which capture/analysis path feeds `freq_data`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+    // AnalyserNode's built-in FFT: fixed Blackman window, no zero-padding.
+    Fast,
+    // AudioWorklet raw samples, analyzed in Rust with a chosen window and
+    // zero-padding factor.
+    Worklet,
+}
+
+// Window function applied to each frame before the custom FFT.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WindowType {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowType {
+    // Window coefficient for sample `n` of a frame of length `size`.
+    fn coefficient(self, n: usize, size: usize) -> f32 {
+        let n = n as f32;
+        let size = size as f32;
+        match self {
+            WindowType::Rectangular => 1.0,
+            WindowType::Hann => 0.5 - 0.5 * (2.0 * PI * n / size).cos(),
+            WindowType::Hamming => 0.54 - 0.46 * (2.0 * PI * n / size).cos(),
+            WindowType::Blackman => {
+                0.42 - 0.5 * (2.0 * PI * n / size).cos() + 0.08 * (4.0 * PI * n / size).cos()
+            }
+        }
+    }
+}
+
+// Control points for the Viridis colormap, at normalized positions
+// 0, 0.25, 0.5, 0.75, 1.0, interpolated linearly between stops.
+const VIRIDIS_STOPS: [(f32, f32, f32); 5] = [
+    (68.0, 1.0, 84.0),
+    (59.0, 82.0, 139.0),
+    (33.0, 145.0, 140.0),
+    (94.0, 201.0, 98.0),
+    (253.0, 231.0, 37.0),
+];
+
+// Control points for the Inferno colormap, same layout as `VIRIDIS_STOPS`.
+const INFERNO_STOPS: [(f32, f32, f32); 5] = [
+    (0.0, 0.0, 4.0),
+    (87.0, 16.0, 110.0),
+    (188.0, 55.0, 84.0),
+    (249.0, 142.0, 9.0),
+    (252.0, 255.0, 164.0),
+];
+
+// Color palette used to render normalized (0..1) magnitudes as CSS colors,
+// for both the bar display and the waterfall columns.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorMap {
+    Rainbow,
+    BlueRed,
+    Viridis,
+    Grayscale,
+    Inferno,
+}
+
+impl ColorMap {
+    // Maps a normalized 0..1 magnitude to a CSS color string.
+    fn to_css_color(self, normalized: f64) -> String {
+        let normalized = normalized.clamp(0.0, 1.0);
+        match self {
+            ColorMap::Rainbow => format!("hsl({}, 100%, 50%)", normalized * 360.0),
+            ColorMap::BlueRed => format!("hsl({}, 100%, 50%)", 240.0 * (1.0 - normalized)),
+            ColorMap::Grayscale => format!("hsl(0, 0%, {}%)", normalized * 100.0),
+            ColorMap::Viridis => Self::interpolate_stops(&VIRIDIS_STOPS, normalized),
+            ColorMap::Inferno => Self::interpolate_stops(&INFERNO_STOPS, normalized),
+        }
+    }
+
+    // Linearly interpolates between a small table of RGB control points.
+    fn interpolate_stops(stops: &[(f32, f32, f32)], normalized: f64) -> String {
+        let last = stops.len() - 1;
+        let scaled = normalized * last as f64;
+        let low = (scaled.floor() as usize).min(last);
+        let high = (low + 1).min(last);
+        let frac = scaled - low as f64;
+
+        let lerp = |a: f32, b: f32| a as f64 + (b as f64 - a as f64) * frac;
+        let (r0, g0, b0) = stops[low];
+        let (r1, g1, b1) = stops[high];
+
+        format!(
+            "rgb({}, {}, {})",
+            lerp(r0, r1) as u32,
+            lerp(g0, g1) as u32,
+            lerp(b0, b1) as u32
+        )
+    }
+}
+
+// Hit-tests a y coordinate against the piano roll's per-note y-bands,
+// returning whichever key's center the click landed closest to.
+fn closest_note_for_y(
+    y: f64,
+    height: f64,
+    min_frequency: f64,
+    max_frequency: f64,
+    scale_type: ScaleType,
+) -> u8 {
+    let scaled_min = scale_type.to_scaled(min_frequency);
+    let scaled_max = scale_type.to_scaled(max_frequency);
+
+    (MIN_MIDI_NOTE..=MAX_MIDI_NOTE)
+        .min_by(|&a, &b| {
+            let y_for = |note: u8| {
+                let scaled_freq = scale_type.to_scaled(midi_note_to_frequency(note as f64));
+                height * (1.0 - (scaled_freq - scaled_min) / (scaled_max - scaled_min))
+            };
+            (y_for(a) - y).abs().total_cmp(&(y_for(b) - y).abs())
+        })
+        .unwrap_or(MIDI_A4)
+}
+
+// Plays a MIDI note through a short attack/release envelope, like a
+// synth preview key.
+fn play_note(context: &AudioContext, note: u8) -> Result<(), JsValue> {
+    let oscillator = context.create_oscillator()?;
+    let gain = context.create_gain()?;
+
+    oscillator
+        .frequency()
+        .set_value(midi_note_to_frequency(note as f64) as f32);
+    oscillator.connect_with_audio_node(&gain)?;
+    gain.connect_with_audio_node(&context.destination())?;
+
+    let now = context.current_time();
+    let gain_param = gain.gain();
+    gain_param.set_value_at_time(0.0, now)?;
+    gain_param.linear_ramp_to_value_at_time(NOTE_PEAK_GAIN, now + NOTE_ATTACK_SECONDS)?;
+    gain_param.exponential_ramp_to_value_at_time(0.0001, now + NOTE_RELEASE_SECONDS)?;
+
+    oscillator.start()?;
+    oscillator.stop_with_when(now + NOTE_RELEASE_SECONDS)?;
+
+    Ok(())
+}
+
 #[wasm_bindgen]
 pub struct Spectrogram {
     context: AudioContext,
@@ -21,11 +269,36 @@ pub struct Spectrogram {
     freq_canvas: HtmlCanvasElement,
     waterfall_canvas: HtmlCanvasElement,
     time_data: Vec<u8>,
-    freq_data: Vec<u8>,
+    freq_data: Vec<f32>,
     waterfall_x: f64,
     piano_roll_width: f64,
     min_frequency: f64,
     max_frequency: f64,
+    gain_db: f64,
+    range_db: f64,
+    freq_gain_db_per_octave: f64,
+    // Shared so the piano-roll click handler's closure always hit-tests
+    // against the scale currently in effect, not the one at attach time.
+    scale_type: Rc<RefCell<ScaleType>>,
+    played_note: Rc<RefCell<Option<u8>>>,
+    // Kept alive for as long as the listener it backs is registered.
+    click_listener: Option<Closure<dyn FnMut(MouseEvent)>>,
+    find_notes_enabled: bool,
+    find_notes_max_notes: usize,
+    find_notes_min_db: f64,
+    capture_mode: CaptureMode,
+    window_type: WindowType,
+    zero_padding_factor: u32,
+    time_domain_frame_size: usize,
+    worklet_node: Option<AudioWorkletNode>,
+    // Mutes the worklet's output while still routing it to the destination,
+    // which is what keeps the engine pulling process() on the node at all.
+    worklet_gain: Option<GainNode>,
+    worklet_buffer: Rc<RefCell<Vec<f32>>>,
+    // Kept alive for as long as the listener it backs is registered.
+    worklet_message_listener: Option<Closure<dyn FnMut(MessageEvent)>>,
+    source_node: Option<MediaStreamAudioSourceNode>,
+    color_map: ColorMap,
 }
 
 #[wasm_bindgen]
@@ -61,7 +334,7 @@ impl Spectrogram {
         let time_data = vec![0; analyser.frequency_bin_count() as usize];
         let freq_data = vec![0; analyser.frequency_bin_count() as usize];
 
-        Ok(Spectrogram {
+        let mut spectrogram = Spectrogram {
             context,
             analyser,
             time_canvas,
@@ -73,7 +346,158 @@ impl Spectrogram {
             piano_roll_width: 40.0, // Width of piano roll in pixels
             min_frequency: 27.5,    // A0 frequency
             max_frequency: 4186.01, // C8 frequency
-        })
+            gain_db: 20.0,          // Audacity default
+            range_db: 80.0,         // Audacity default
+            freq_gain_db_per_octave: 0.0,
+            scale_type: Rc::new(RefCell::new(ScaleType::Log)),
+            played_note: Rc::new(RefCell::new(None)),
+            click_listener: None,
+            find_notes_enabled: false,
+            find_notes_max_notes: 5,
+            find_notes_min_db: -60.0,
+            capture_mode: CaptureMode::Fast,
+            window_type: WindowType::Hann,
+            zero_padding_factor: 1,
+            time_domain_frame_size: 2048,
+            worklet_node: None,
+            worklet_gain: None,
+            worklet_buffer: Rc::new(RefCell::new(Vec::new())),
+            worklet_message_listener: None,
+            source_node: None,
+            color_map: ColorMap::Rainbow,
+        };
+        spectrogram.attach_piano_roll_click_handler()?;
+
+        Ok(spectrogram)
+    }
+
+    // Wires up a click handler on the waterfall canvas that hit-tests the
+    // piano roll keys and plays the corresponding pitch through a short
+    // attack/release envelope, like a DAW piano-roll header.
+    fn attach_piano_roll_click_handler(&mut self) -> Result<(), JsValue> {
+        let canvas = self.waterfall_canvas.clone();
+        let context = self.context.clone();
+        let piano_roll_width = self.piano_roll_width;
+        let min_frequency = self.min_frequency;
+        let max_frequency = self.max_frequency;
+        let scale_type = self.scale_type.clone();
+        let played_note = self.played_note.clone();
+
+        let listener = Closure::wrap(Box::new(move |event: MouseEvent| {
+            let rect = canvas.get_bounding_client_rect();
+            let x = event.client_x() as f64 - rect.left();
+            let y = event.client_y() as f64 - rect.top();
+            if x < 0.0 || x >= piano_roll_width {
+                return;
+            }
+
+            let height = canvas.height() as f64;
+            let note =
+                closest_note_for_y(y, height, min_frequency, max_frequency, *scale_type.borrow());
+
+            if play_note(&context, note).is_ok() {
+                *played_note.borrow_mut() = Some(note);
+
+                let played_note = played_note.clone();
+                let clear_highlight = Closure::once_into_js(move || {
+                    // Only clear the highlight if we're still the most
+                    // recently played note — an overlapping click may have
+                    // already moved played_note on and started its own timer.
+                    if *played_note.borrow() == Some(note) {
+                        *played_note.borrow_mut() = None;
+                    }
+                });
+                if let Some(window) = web_sys::window() {
+                    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                        clear_highlight.unchecked_ref(),
+                        (NOTE_RELEASE_SECONDS * 1000.0) as i32,
+                    );
+                }
+            }
+        }) as Box<dyn FnMut(MouseEvent)>);
+
+        self.waterfall_canvas
+            .add_event_listener_with_callback("click", listener.as_ref().unchecked_ref())?;
+        self.click_listener = Some(listener);
+
+        Ok(())
+    }
+
+    /// Selects the frequency axis scale for the piano roll and waterfall.
+    pub fn set_scale_type(&mut self, scale_type: ScaleType) {
+        *self.scale_type.borrow_mut() = scale_type;
+    }
+
+    /// Brightens or dims the whole spectrum before it's normalized to 0..1.
+    pub fn set_gain_db(&mut self, gain_db: f64) {
+        self.gain_db = gain_db;
+    }
+
+    /// Sets the dB span mapped to the full 0..1 display range (the "floor").
+    pub fn set_range_db(&mut self, range_db: f64) {
+        self.range_db = range_db.max(1.0);
+    }
+
+    /// Adds `freq_gain_db_per_octave * log2(freq / min_frequency)` to each
+    /// bin's dB before normalization, to compensate for high-frequency rolloff.
+    pub fn set_freq_gain_db_per_octave(&mut self, freq_gain_db_per_octave: f64) {
+        self.freq_gain_db_per_octave = freq_gain_db_per_octave;
+    }
+
+    // Maps a bin's raw dB value (plus optional per-octave frequency
+    // compensation) to a normalized 0..1 display value, Audacity-style.
+    fn normalized_magnitude(&self, db: f64, freq: f64) -> f64 {
+        normalize_magnitude(
+            db,
+            freq,
+            self.gain_db,
+            self.range_db,
+            self.freq_gain_db_per_octave,
+            self.min_frequency,
+        )
+    }
+
+    /// Turns the "find notes" overlay on or off. While on, it keeps at most
+    /// `max_notes` of the strongest peaks at or above `min_db` and marks them
+    /// on the waterfall and piano roll each frame.
+    pub fn set_find_notes(&mut self, enabled: bool, max_notes: usize, min_db: f64) {
+        self.find_notes_enabled = enabled;
+        self.find_notes_max_notes = max_notes;
+        self.find_notes_min_db = min_db;
+    }
+
+    // Detects local spectral peaks, refines each with parabolic
+    // interpolation over its three neighboring log-magnitudes, and returns
+    // the strongest ones as (midi_note, frequency) pairs.
+    fn detect_notes(&self) -> Vec<(u8, f64)> {
+        if !self.find_notes_enabled || self.freq_data.len() < 3 {
+            return Vec::new();
+        }
+
+        let sample_rate = self.context.sample_rate() as f64;
+        let fft_size = self.effective_fft_size();
+
+        let mut peaks: Vec<(f64, f64)> = Vec::new(); // (magnitude_db, frequency)
+        for i in 1..self.freq_data.len() - 1 {
+            let a = self.freq_data[i - 1] as f64;
+            let b = self.freq_data[i] as f64;
+            let c = self.freq_data[i + 1] as f64;
+
+            if b <= a || b <= c || b < self.find_notes_min_db {
+                continue;
+            }
+
+            let bin = i as f64 + parabolic_peak_delta(a, b, c);
+            let freq = bin * sample_rate / fft_size;
+            peaks.push((b, freq));
+        }
+
+        peaks.sort_by(|x, y| y.0.total_cmp(&x.0));
+        peaks
+            .into_iter()
+            .take(self.find_notes_max_notes)
+            .map(|(_, freq)| (frequency_to_midi_note(freq).round() as u8, freq))
+            .collect()
     }
 
     pub async fn start(&mut self) -> Result<(), JsValue> {
@@ -90,28 +514,177 @@ impl Spectrogram {
 
         let source = self.context.create_media_stream_source(&media_stream)?;
         source.connect_with_audio_node(&self.analyser)?;
+        if let Some(worklet_node) = &self.worklet_node {
+            source.connect_with_audio_node(worklet_node)?;
+        }
+        self.source_node = Some(source);
 
         Ok(())
     }
 
-    // Helper method to convert frequency to MIDI note number
-    fn frequency_to_midi_note(&self, freq: f64) -> f64 {
-        12.0 * (freq / A4_FREQUENCY).log2() + MIDI_A4 as f64
+    /// Switches between the fast `AnalyserNode` path and the AudioWorklet
+    /// path with a custom window/zero-padding FFT, (re)sizing `freq_data`
+    /// to match. Lazily sets up the worklet node on first use.
+    pub async fn set_capture_mode(&mut self, mode: CaptureMode) -> Result<(), JsValue> {
+        if mode == CaptureMode::Worklet && self.worklet_node.is_none() {
+            self.init_audio_worklet().await?;
+        }
+
+        self.capture_mode = mode;
+        self.resize_freq_data();
+
+        Ok(())
+    }
+
+    /// Selects the window function used by the AudioWorklet analysis path.
+    pub fn set_window(&mut self, window_type: WindowType) {
+        self.window_type = window_type;
+    }
+
+    /// Sets how many multiples of the frame length to zero-pad to before
+    /// the custom FFT (`k=1` means no padding) on the AudioWorklet path.
+    pub fn set_zero_padding_factor(&mut self, zero_padding_factor: u32) {
+        self.zero_padding_factor = zero_padding_factor.max(1);
+        self.resize_freq_data();
     }
 
-    // Helper method to convert MIDI note to frequency
-    fn midi_note_to_frequency(&self, note: f64) -> f64 {
-        A4_FREQUENCY * 2.0_f64.powf((note - MIDI_A4 as f64) / 12.0)
+    /// Selects the color palette used for the bar display and the
+    /// waterfall columns.
+    pub fn set_color_map(&mut self, color_map: ColorMap) {
+        self.color_map = color_map;
     }
 
-    // Maps a frequency to y position using logarithmic scale
+    // Resizes `freq_data` to match the active capture mode's bin count.
+    fn resize_freq_data(&mut self) {
+        let bin_count = match self.capture_mode {
+            CaptureMode::Fast => self.analyser.frequency_bin_count() as usize,
+            CaptureMode::Worklet => {
+                (self.time_domain_frame_size * self.zero_padding_factor as usize) / 2 + 1
+            }
+        };
+        self.freq_data = vec![f32::NEG_INFINITY; bin_count];
+    }
+
+    // The FFT size backing the current `freq_data` (including zero-padding
+    // on the AudioWorklet path), used to convert bin indices to/from Hz.
+    fn effective_fft_size(&self) -> f64 {
+        match self.capture_mode {
+            CaptureMode::Fast => self.analyser.fft_size() as f64,
+            CaptureMode::Worklet => {
+                (self.time_domain_frame_size * self.zero_padding_factor as usize) as f64
+            }
+        }
+    }
+
+    // Loads the capture-processor AudioWorklet module, wires up a node that
+    // streams raw time-domain frames back over its message port, and
+    // connects it to the already-running media stream source, if any.
+    async fn init_audio_worklet(&mut self) -> Result<(), JsValue> {
+        let worklet = self.context.audio_worklet()?;
+        let module_promise = worklet.add_module(CAPTURE_PROCESSOR_MODULE_URL)?;
+        JsFuture::from(module_promise).await?;
+
+        let node = AudioWorkletNode::new(&self.context, CAPTURE_PROCESSOR_NAME)?;
+
+        let buffer = self.worklet_buffer.clone();
+        let frame_size = self.time_domain_frame_size;
+        let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+            if let Ok(samples) = event.data().dyn_into::<js_sys::Float32Array>() {
+                let mut buffer = buffer.borrow_mut();
+                // The processor posts one 128-frame render quantum per
+                // message, so accumulate across messages and keep only the
+                // most recent frame_size samples (a sliding window) rather
+                // than overwriting with just the latest quantum.
+                buffer.extend(samples.to_vec());
+                if buffer.len() > frame_size {
+                    let excess = buffer.len() - frame_size;
+                    buffer.drain(..excess);
+                }
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+
+        node.port()?
+            .set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        self.worklet_message_listener = Some(on_message);
+
+        // A node only has process() pulled if it's reachable from the
+        // destination, so route the worklet's (unused) output through a
+        // muted gain node rather than leaving it dangling.
+        let gain = self.context.create_gain()?;
+        gain.gain().set_value(0.0);
+        node.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&self.context.destination())?;
+        self.worklet_gain = Some(gain);
+
+        if let Some(source_node) = &self.source_node {
+            source_node.connect_with_audio_node(&node)?;
+        }
+        self.worklet_node = Some(node);
+
+        Ok(())
+    }
+
+    // Computes the spectrum for the current frame on the AudioWorklet path:
+    // windows the raw samples, zero-pads, runs an FFT, and converts each
+    // bin to dB, matching the scale `get_float_frequency_data` returns.
+    fn compute_worklet_spectrum(&mut self) {
+        let raw = self.worklet_buffer.borrow();
+        if raw.len() < self.time_domain_frame_size {
+            return;
+        }
+
+        let padded_len = self.time_domain_frame_size * self.zero_padding_factor as usize;
+        let mut samples: Vec<Complex<f32>> = raw[..self.time_domain_frame_size]
+            .iter()
+            .enumerate()
+            .map(|(n, &sample)| {
+                let window = self.window_type.coefficient(n, self.time_domain_frame_size);
+                Complex::new(sample * window, 0.0)
+            })
+            .collect();
+        drop(raw);
+        samples.resize(padded_len, Complex::new(0.0, 0.0));
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(padded_len);
+        fft.process(&mut samples);
+
+        let bin_count = padded_len / 2 + 1;
+        if self.freq_data.len() != bin_count {
+            self.freq_data = vec![f32::NEG_INFINITY; bin_count];
+        }
+
+        let last_bin = bin_count - 1;
+        for (i, bin) in samples.iter().take(bin_count).enumerate() {
+            // The one-sided spectrum's non-DC/non-Nyquist bins split energy
+            // with their mirrored negative-frequency image, so double them
+            // to recover the true one-sided magnitude.
+            let scale = if i == 0 || i == last_bin { 1.0 } else { 2.0 };
+            let magnitude = scale * bin.norm() / self.time_domain_frame_size as f32;
+            self.freq_data[i] = 20.0 * magnitude.max(1e-12).log10();
+        }
+    }
+
+    // Maps a frequency to a y position using the current axis scale.
     fn frequency_to_y_position(&self, freq: f64, height: f64) -> f64 {
-        let log_min = self.min_frequency.ln();
-        let log_max = self.max_frequency.ln();
-        let log_freq = freq.ln();
+        let scale_type = *self.scale_type.borrow();
+        let scaled_min = scale_type.to_scaled(self.min_frequency);
+        let scaled_max = scale_type.to_scaled(self.max_frequency);
+        let scaled_freq = scale_type.to_scaled(freq);
 
         // Invert y-axis (0 at top, height at bottom)
-        height * (1.0 - (log_freq - log_min) / (log_max - log_min))
+        height * (1.0 - (scaled_freq - scaled_min) / (scaled_max - scaled_min))
+    }
+
+    // Inverts `frequency_to_y_position`: recovers the frequency a waterfall
+    // row represents, so FFT bins can be resampled onto it.
+    fn y_position_to_frequency(&self, y: f64, height: f64) -> f64 {
+        let scale_type = *self.scale_type.borrow();
+        let scaled_min = scale_type.to_scaled(self.min_frequency);
+        let scaled_max = scale_type.to_scaled(self.max_frequency);
+
+        let scaled_freq = scaled_min + (1.0 - y / height) * (scaled_max - scaled_min);
+        scale_type.from_scaled(scaled_freq)
     }
 
     // Draw the piano roll on the canvas
@@ -119,15 +692,27 @@ impl Spectrogram {
         &self,
         ctx: &web_sys::CanvasRenderingContext2d,
         height: f64,
+        detected_notes: &[u8],
     ) -> Result<(), JsValue> {
         // Draw piano roll background
         ctx.set_fill_style_str("#222");
         ctx.fill_rect(0.0, 0.0, self.piano_roll_width, height);
 
+        let played_note = *self.played_note.borrow();
+
         // Draw piano keys
         for note in MIN_MIDI_NOTE..=MAX_MIDI_NOTE {
-            let note_freq = self.midi_note_to_frequency(note as f64);
+            let note_freq = midi_note_to_frequency(note as f64);
             let y = self.frequency_to_y_position(note_freq, height);
+            let is_played = played_note == Some(note);
+            let is_detected = detected_notes.contains(&note);
+            let highlight_color = if is_played {
+                Some("#ffca28")
+            } else if is_detected {
+                Some("#4caf50")
+            } else {
+                None
+            };
 
             // Calculate the note index (0-11) to determine if it's white or black key
             let note_idx = (note % 12) as usize;
@@ -135,7 +720,7 @@ impl Spectrogram {
 
             // Draw the key
             if is_white {
-                ctx.set_fill_style_str("#aaa");
+                ctx.set_fill_style_str(highlight_color.unwrap_or("#aaa"));
                 ctx.fill_rect(0.0, y - 1.0, self.piano_roll_width, 2.0);
 
                 // Draw note name for C notes (and A4 for reference)
@@ -153,8 +738,16 @@ impl Spectrogram {
                     ctx.fill_text(&note_name, 3.0, y - 3.0)?;
                 }
             } else {
-                ctx.set_fill_style_str("#666");
-                ctx.fill_rect(0.0, y - 0.5, self.piano_roll_width * 0.6, 1.0);
+                // Black keys sit shorter and flush against the spectrogram
+                // divider, like the back edge of a real keyboard.
+                let black_key_width = self.piano_roll_width * 0.6;
+                ctx.set_fill_style_str(highlight_color.unwrap_or("#111"));
+                ctx.fill_rect(
+                    self.piano_roll_width - black_key_width,
+                    y - 2.0,
+                    black_key_width,
+                    4.0,
+                );
             }
         }
 
@@ -219,16 +812,22 @@ impl Spectrogram {
         freq_ctx.set_fill_style_str("#000");
         freq_ctx.fill_rect(0.0, 0.0, freq_width, freq_height);
 
-        self.analyser.get_byte_frequency_data(&mut self.freq_data);
+        match self.capture_mode {
+            CaptureMode::Fast => self.analyser.get_float_frequency_data(&mut self.freq_data),
+            CaptureMode::Worklet => self.compute_worklet_spectrum(),
+        }
 
+        let sample_rate = self.context.sample_rate() as f64;
+        let fft_size = self.effective_fft_size();
         let bar_width = freq_width / self.freq_data.len() as f64;
         let mut x = 0.0;
 
-        for &value in self.freq_data.iter() {
-            let bar_height = (value as f64 / 255.0) * freq_height;
+        for (i, &value) in self.freq_data.iter().enumerate() {
+            let freq = i as f64 * sample_rate / fft_size;
+            let normalized_value = self.normalized_magnitude(value as f64, freq);
+            let bar_height = normalized_value * freq_height;
 
-            let hue = x / freq_width * 360.0;
-            freq_ctx.set_fill_style_str(&format!("hsl({}, 100%, {}%)", hue, 50.0));
+            freq_ctx.set_fill_style_str(&self.color_map.to_css_color(normalized_value));
 
             freq_ctx.fill_rect(x, freq_height - bar_height, bar_width, bar_height);
 
@@ -247,6 +846,9 @@ impl Spectrogram {
         let waterfall_width = self.waterfall_canvas.width() as f64;
         let waterfall_height = self.waterfall_canvas.height() as f64;
 
+        let detected_peaks = self.detect_notes();
+        let detected_notes: Vec<u8> = detected_peaks.iter().map(|&(note, _)| note).collect();
+
         // Only clear and redraw the piano roll and current x-position, not the entire canvas
         // This preserves the historical data in the waterfall
 
@@ -255,7 +857,7 @@ impl Spectrogram {
         waterfall_ctx.fill_rect(0.0, 0.0, self.piano_roll_width, waterfall_height);
 
         // Draw piano roll
-        self.draw_piano_roll(&waterfall_ctx, waterfall_height)?;
+        self.draw_piano_roll(&waterfall_ctx, waterfall_height, &detected_notes)?;
 
         // Adjust waterfall area to accommodate piano roll
         let adjusted_width = waterfall_width - self.piano_roll_width;
@@ -267,7 +869,7 @@ impl Spectrogram {
             waterfall_ctx.fill_rect(0.0, 0.0, waterfall_width, waterfall_height);
 
             // Redraw the piano roll
-            self.draw_piano_roll(&waterfall_ctx, waterfall_height)?;
+            self.draw_piano_roll(&waterfall_ctx, waterfall_height, &detected_notes)?;
 
             // Start after the piano roll
             self.waterfall_x = self.piano_roll_width;
@@ -279,18 +881,32 @@ impl Spectrogram {
         waterfall_ctx.set_fill_style_str("#000");
         waterfall_ctx.fill_rect(self.waterfall_x, 0.0, 1.0, waterfall_height);
 
-        // Calculate height of each frequency bin in pixels
-        // Note: we're using logarithmic frequency mapping for the piano roll
-        // but the FFT data still uses linear mapping
-        let bar_height = waterfall_height / self.freq_data.len() as f64;
-
-        // Draw new line at current x position
-        for (i, &value) in self.freq_data.iter().rev().enumerate() {
-            let y = i as f64 * bar_height;
-            let normalized_value = value as f64 / 255.0;
-            let hue = 240.0 * (1.0 - normalized_value); // Blue (240) to Red (0)
-            waterfall_ctx.set_fill_style_str(&format!("hsl({}, 100%, {}%)", hue, 50.0));
-            waterfall_ctx.fill_rect(self.waterfall_x, y, 1.0, bar_height);
+        // Draw new line at current x position. Each output row is resampled
+        // from the (linearly-spaced) FFT bins so the waterfall lines up with
+        // the piano roll's axis scale, one row per pixel.
+        let last_bin = self.freq_data.len() - 1;
+        let mut y = 0.0;
+        while y < waterfall_height {
+            let freq = self.y_position_to_frequency(y, waterfall_height);
+            let bin_position = (freq * fft_size / sample_rate).clamp(0.0, last_bin as f64);
+            let bin_low = bin_position.floor() as usize;
+            let bin_high = (bin_low + 1).min(last_bin);
+            let frac = bin_position - bin_low as f64;
+
+            let value = self.freq_data[bin_low] as f64 * (1.0 - frac)
+                + self.freq_data[bin_high] as f64 * frac;
+            let normalized_value = self.normalized_magnitude(value, freq);
+            waterfall_ctx.set_fill_style_str(&self.color_map.to_css_color(normalized_value));
+            waterfall_ctx.fill_rect(self.waterfall_x, y, 1.0, 1.0);
+
+            y += 1.0;
+        }
+
+        // Mark detected note peaks on top of the freshly drawn column
+        waterfall_ctx.set_fill_style_str("#00eaff");
+        for &(_, freq) in &detected_peaks {
+            let y = self.frequency_to_y_position(freq, waterfall_height);
+            waterfall_ctx.fill_rect(self.waterfall_x, y - 1.0, 1.0, 2.0);
         }
 
         // Move to next x position
@@ -299,3 +915,102 @@ impl Spectrogram {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_magnitude_clamps_to_unit_range() {
+        // compensated_db == -range_db - gain_db maps to exactly 0.0
+        let low = normalize_magnitude(-80.0, 1000.0, 0.0, 80.0, 0.0, 20.0);
+        assert_eq!(low, 0.0);
+
+        // compensated_db == -gain_db maps to exactly 1.0
+        let high = normalize_magnitude(0.0, 1000.0, 0.0, 80.0, 0.0, 20.0);
+        assert_eq!(high, 1.0);
+
+        // Values outside the floor/ceiling still clamp rather than overflow.
+        let below_floor = normalize_magnitude(-200.0, 1000.0, 0.0, 80.0, 0.0, 20.0);
+        assert_eq!(below_floor, 0.0);
+        let above_ceiling = normalize_magnitude(50.0, 1000.0, 0.0, 80.0, 0.0, 20.0);
+        assert_eq!(above_ceiling, 1.0);
+    }
+
+    #[test]
+    fn normalize_magnitude_dc_bin_does_not_produce_nan() {
+        let value = normalize_magnitude(-40.0, 0.0, 0.0, 80.0, 6.0, 20.0);
+        assert!(!value.is_nan());
+    }
+
+    #[test]
+    fn mel_and_freq_round_trip() {
+        for freq in [20.0, 440.0, 1000.0, 8000.0, 20000.0] {
+            let mel = ScaleType::freq_to_mel(freq);
+            let round_tripped = ScaleType::mel_to_freq(mel);
+            assert!(
+                (round_tripped - freq).abs() < 1e-6,
+                "expected {freq} to round-trip through mel space, got {round_tripped}"
+            );
+        }
+    }
+
+    #[test]
+    fn parabolic_peak_delta_locates_synthetic_peak() {
+        // A parabola y = -(x - 0.3)^2 sampled at integer bins -1, 0, 1 has
+        // its true peak 0.3 bins to the right of bin 0.
+        let true_peak_offset = 0.3;
+        let a = -(-1.0 - true_peak_offset as f64).powi(2);
+        let b = -(0.0 - true_peak_offset as f64).powi(2);
+        let c = -(1.0 - true_peak_offset as f64).powi(2);
+
+        let delta = parabolic_peak_delta(a, b, c);
+        assert!(
+            (delta - true_peak_offset).abs() < 1e-9,
+            "expected delta close to {true_peak_offset}, got {delta}"
+        );
+    }
+
+    #[test]
+    fn parabolic_peak_delta_is_zero_for_collinear_points() {
+        assert_eq!(parabolic_peak_delta(1.0, 2.0, 3.0), 0.0);
+    }
+
+    #[test]
+    fn window_coefficients_at_the_first_sample() {
+        // Hann and Blackman taper to zero at n=0, Hamming to 0.08;
+        // Rectangular stays flat at 1.0 regardless of n.
+        assert_eq!(WindowType::Rectangular.coefficient(0, 1024), 1.0);
+        assert_eq!(WindowType::Hann.coefficient(0, 1024), 0.0);
+        assert!((WindowType::Hamming.coefficient(0, 1024) - 0.08).abs() < 1e-6);
+        assert!((WindowType::Blackman.coefficient(0, 1024) - 0.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn interpolate_stops_returns_endpoints_exactly() {
+        let stops = VIRIDIS_STOPS;
+        let (r0, g0, b0) = stops[0];
+        let (r1, g1, b1) = stops[stops.len() - 1];
+
+        assert_eq!(
+            ColorMap::interpolate_stops(&stops, 0.0),
+            format!("rgb({}, {}, {})", r0 as u32, g0 as u32, b0 as u32)
+        );
+        assert_eq!(
+            ColorMap::interpolate_stops(&stops, 1.0),
+            format!("rgb({}, {}, {})", r1 as u32, g1 as u32, b1 as u32)
+        );
+    }
+
+    #[test]
+    fn interpolate_stops_midpoint_lands_on_middle_stop() {
+        // VIRIDIS_STOPS has 5 control points at 0, 0.25, 0.5, 0.75, 1.0, so
+        // normalized 0.5 should land exactly on the middle stop.
+        let stops = VIRIDIS_STOPS;
+        let (r, g, b) = stops[2];
+        assert_eq!(
+            ColorMap::interpolate_stops(&stops, 0.5),
+            format!("rgb({}, {}, {})", r as u32, g as u32, b as u32)
+        );
+    }
+}